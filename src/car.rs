@@ -1,58 +1,148 @@
 use std::f32::consts::TAU;
 use bevy::{prelude::*, sprite::MaterialMesh2dBundle};
+use crate::track::{TrackConfig, LapProgress};
 
-/// A resource containing information about the track
+/// A resource containing physical simulations constants such as acceleration and friction
 #[derive(Clone, Debug, Reflect, Resource)]
-pub struct TrackConfig{
-    /// Logical width and height of the track
-    pub logical_size: Vec2,
-    /// Scale of the track in the world coordinates
-    pub scale: f32
+pub struct CarPhysicsConfig{
+    /// Distance between the front and rear axles
+    pub wheelbase: f32,
+    /// How fast the front wheel's steer angle approaches its target, in radians/second
+    pub steering_speed: f32,
+    /// The furthest the front wheel can be steered away from the body heading, in radians
+    pub max_steer_angle: f32,
+    pub forward_acceleration: f32,
+    pub friction: f32,
+    /// How strongly the front axle resists sliding sideways
+    pub front_grip: f32,
+    /// How strongly the rear axle resists sliding sideways. Lower than `front_grip` induces oversteer
+    pub rear_grip: f32,
+    /// The most lateral force an axle's tires can exert before they give up and let the car slide
+    pub max_grip_force: f32,
+    pub max_forward_velocity: f32,
+    /// Distance a car is pulled back from a wall hit point, so it doesn't immediately re-collide
+    pub wall_skin: f32,
+    /// Consecutive steps a car can be caught against a wall before it is nudged free of the corner
+    pub max_stuck_frames: u32,
+    /// Magnitude of one g, in logical units/second^2, used to convert acceleration into g-force
+    pub gravity: f32,
+    /// G-force above which a car's tires temporarily wash out
+    pub grip_loss_g: f32,
+    /// Grip multiplier applied to both axles while washed out
+    pub grip_loss_scale: f32,
+    /// How long, in seconds, a grip-loss hit lasts before the tires recover
+    pub grip_loss_duration: f32
 }
-impl Default for TrackConfig{
+impl Default for CarPhysicsConfig{
     fn default() -> Self {
-        Self{logical_size: Vec2::new(100.0, 100.0), scale: 1.0}
+        Self {
+            wheelbase: 4.0,
+            steering_speed: 8.0,
+            max_steer_angle: 0.6,
+            forward_acceleration: 200.0,
+            friction: 0.97,
+            front_grip: 10.0,
+            rear_grip: 8.0,
+            max_grip_force: 400.0,
+            max_forward_velocity: 150.0,
+            wall_skin: 0.1,
+            max_stuck_frames: 10,
+            gravity: 9.81,
+            grip_loss_g: 3.0,
+            grip_loss_scale: 0.4,
+            grip_loss_duration: 0.5
+        }
     }
 }
-impl TrackConfig{
-    /// Transforms a track position to a world position
-    pub fn track_to_world(&self, track_coords: Vec2)->Vec2{
-        (track_coords - self.logical_size*0.5)*self.scale
-    }
-    /// Transforms a world position to a track position
-    pub fn world_to_track(&self, world_pos: Vec2) -> Vec2{
-        (world_pos/self.scale) + self.logical_size*0.5
-    }
-    /// Updates the scale of the track to be as large as possible while keeping the entire track within bounds of the window
-    pub fn compute_scale(&mut self, window_size: Vec2){
-        self.scale = (window_size / self.logical_size).min_element();
+
+/// Stores a car's velocity at the start of the physics step, used to compute instantaneous
+/// acceleration for g-force tracking
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct PreviousVelocity(pub Vec2);
+
+/// Tracks the g-force a car is currently experiencing and the hardest hit it has taken
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct ExperiencesGForce{
+    pub current: f32,
+    pub peak: f32,
+    /// Seconds remaining on a grip-loss window armed by a hard hit or corner
+    pub grip_loss_timer: f32
+}
+impl ExperiencesGForce{
+    /// System which records each car's velocity before `TrackTransform::update_physics` runs
+    pub fn record_previous_velocity(mut cars: Query<(&mut PreviousVelocity, &TrackTransform)>){
+        for (mut previous, transform) in cars.iter_mut(){
+            previous.0 = transform.velocity;
+        }
     }
-    /// A system which queries for window size and updates the scale accordingly
-    pub fn update_scale(mut config: ResMut<Self>, windows: Query<&Window>){
-        let Ok(window) = windows.get_single() else {return;};
-        config.compute_scale(Vec2::new(window.height(), window.width()));
+    /// System which computes each car's instantaneous g-force from the change in velocity this
+    /// step (including collision impulses from `Tunneling::resolve_wall_collisions`), tracks its
+    /// peak, and arms a temporary grip-loss window on a `grip_loss_g` hit so hard impacts and
+    /// extreme cornering wash the tires out for a few frames
+    pub fn update_gforce(
+        mut cars: Query<(&mut Self, &PreviousVelocity, &TrackTransform)>,
+        config: Res<CarPhysicsConfig>,
+        time: Res<Time>
+    ){
+        let dt = time.delta_seconds();
+        if dt <= 0.0 {return;}
+        for (mut gforce, previous, transform) in cars.iter_mut(){
+            let acceleration = (transform.velocity - previous.0)/dt;
+            let g = acceleration.length()/config.gravity;
+            gforce.current = g;
+            gforce.peak = gforce.peak.max(g);
+            if g > config.grip_loss_g{
+                gforce.grip_loss_timer = config.grip_loss_duration;
+            } else {
+                gforce.grip_loss_timer = (gforce.grip_loss_timer - dt).max(0.0);
+            }
+        }
     }
 }
 
-/// A resource containing physical simulations constants such as acceleration and friction
-#[derive(Clone, Debug, Reflect, Resource)]
-pub struct CarPhysicsConfig{
-    pub rotational_acceleration: f32,
-    pub max_rotational_velocity: f32,
-    pub forward_acceleration: f32,
-    pub friction: f32,
-    pub drift_factor: f32,
-    pub max_forward_velocity: f32
+/// Stores a car's position at the start of the physics step, giving the swept segment that
+/// `resolve_wall_collisions` tests against the track boundary for continuous collision detection
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct PreviousPosition(pub Vec2);
+
+/// Tracks how many consecutive physics steps a car has been caught against the track boundary,
+/// and the wall normal to nudge it along if it stays wedged in a corner too long
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct Tunneling{
+    pub frames: u32,
+    pub dir: Vec2
 }
-impl Default for CarPhysicsConfig{
-    fn default() -> Self {
-        Self { 
-            rotational_acceleration: 50.0, 
-            max_rotational_velocity: TAU, 
-            forward_acceleration: 200.0, 
-            friction: 0.97, 
-            drift_factor: 0.99, 
-            max_forward_velocity: 150.0
+impl Tunneling{
+    /// System which records each car's position before `TrackTransform::update_physics` runs
+    pub fn record_previous_position(mut cars: Query<(&mut PreviousPosition, &TrackTransform)>){
+        for (mut previous, transform) in cars.iter_mut(){
+            previous.0 = transform.position;
+        }
+    }
+    /// System which sweeps each car's movement this step against the track boundary, clamping it
+    /// to the earliest wall hit, killing the into-wall velocity while keeping the tangential
+    /// slide, and nudging cars free that stay wedged in a corner for too many steps
+    pub fn resolve_wall_collisions(
+        mut cars: Query<(&mut TrackTransform, &PreviousPosition, &mut Tunneling)>,
+        track: Res<TrackConfig>,
+        config: Res<CarPhysicsConfig>
+    ){
+        for (mut transform, previous, mut tunneling) in cars.iter_mut(){
+            let Some((hit, normal)) = track.sweep_boundary(previous.0, transform.position) else {
+                tunneling.frames = 0;
+                continue;
+            };
+            transform.position = hit + normal*config.wall_skin;
+            let into_wall = transform.velocity.dot(normal);
+            if into_wall < 0.0 {
+                transform.velocity -= normal*into_wall;
+            }
+            tunneling.frames += 1;
+            tunneling.dir = normal;
+            if tunneling.frames > config.max_stuck_frames{
+                transform.position += tunneling.dir*config.wall_skin*2.0;
+                tunneling.frames = 0;
+            }
         }
     }
 }
@@ -107,8 +197,8 @@ pub struct TrackTransform{
     pub rotation: f32,
     /// velocity of the entity
     pub velocity: Vec2,
-    /// Rotational velocity of the entity
-    pub rotational_velocity: f32
+    /// Current steer angle of the front wheel, relative to `rotation`
+    pub steer_angle: f32
 }
 impl TrackTransform{
     /// System to update the world transform using the position in the track transform
@@ -119,44 +209,71 @@ impl TrackTransform{
                 Transform::from_rotation(Quat::from_rotation_z(track.rotation));
         }
     }
-    /// System to run physics step for Track Transform components using car controls
+    /// System to run physics step for Track Transform components using car controls.
+    ///
+    /// Simulates a two-axle bicycle model: the front and rear axle positions are advanced
+    /// independently using their own tire-grip-corrected velocities, and the body's heading
+    /// and position are then derived from the vector between the two updated axle positions.
     pub fn update_physics(
-        mut cars: Query<(&mut Self, &CarControls)>,
+        mut cars: Query<(&mut Self, &CarControls, Option<&ExperiencesGForce>)>,
         config: Res<CarPhysicsConfig>,
         time: Res<Time>
     ){
         let dt = time.delta_seconds();
-        for (mut transform, controls) in cars.iter_mut(){
+        if dt <= 0.0 {return;}
+        for (mut transform, controls, gforce) in cars.iter_mut(){
+            // A hard hit or extreme lateral load washes the tires out for a few frames
+            let grip_scale = if gforce.is_some_and(|g| g.grip_loss_timer > 0.0) {config.grip_loss_scale} else {1.0};
             //Get controls to be normalized
             let accel_control = (controls.accel.x.clamp(0.0, 1.0) - controls.accel.y.clamp(0.0, 1.0)).clamp(-1.0, 1.0);
             let mut turn_control = (controls.turn.x.clamp(0.0, 1.0) - controls.turn.y.clamp(0.0, 1.0)).clamp(-1.0, 1.0);
             if turn_control.abs() < 0.0001 {turn_control = 0.0;}
-            // update rotation
-            if turn_control == 0.0{
-                transform.rotational_velocity = 0.0;
-            }else {
-                transform.rotational_velocity += turn_control*config.rotational_acceleration*dt;
-                transform.rotational_velocity = transform.rotational_velocity.clamp(-config.max_rotational_velocity, config.max_rotational_velocity);
-                transform.rotation += transform.rotational_velocity*dt;
-                transform.rotation = transform.rotation.rem_euclid(TAU);
+            // Steer the front wheel towards the target angle, self-centering as the input is released
+            let target_steer = turn_control*config.max_steer_angle;
+            transform.steer_angle += (target_steer - transform.steer_angle).clamp(-config.steering_speed*dt, config.steering_speed*dt);
+
+            let half_wheelbase = config.wheelbase*0.5;
+            let heading = Vec2::from_angle(transform.rotation);
+            let rear_pos = transform.position - heading*half_wheelbase;
+            let front_pos = transform.position + heading*half_wheelbase;
+            let rear_dir = heading;
+            let front_dir = Vec2::from_angle(transform.rotation + transform.steer_angle);
+
+            // Drive force is applied at the rear axle along the body heading, then friction and
+            // per-axle grip are applied to each axle's estimate of the shared body velocity
+            let rear_velocity = (transform.velocity + heading*accel_control*dt*config.forward_acceleration)*config.friction;
+            let front_velocity = transform.velocity*config.friction;
+            let rear_velocity = Self::apply_axle_grip(rear_velocity, rear_dir, config.rear_grip*grip_scale, config.max_grip_force, dt);
+            let front_velocity = Self::apply_axle_grip(front_velocity, front_dir, config.front_grip*grip_scale, config.max_grip_force, dt);
+
+            // Advance each axle along its own corrected velocity, then derive the new body pose
+            let new_rear_pos = rear_pos + rear_velocity*dt;
+            let new_front_pos = front_pos + front_velocity*dt;
+            let new_position = (new_front_pos + new_rear_pos)*0.5;
+            let heading_vector = new_front_pos - new_rear_pos;
+            if heading_vector.length_squared() > 1e-8{
+                transform.rotation = heading_vector.to_angle().rem_euclid(TAU);
             }
-            let forward_vector = Vec2::from_angle(transform.rotation);
-            // Accelerate
-            let acceleration = forward_vector*accel_control*dt*config.forward_acceleration;
-            transform.velocity += acceleration;
-            // Apply friction
-            transform.velocity *= config.friction;
-            // Apply drift and clamp speed
-            transform.velocity = transform.velocity.project_onto_normalized(forward_vector).lerp(transform.velocity, config.drift_factor).clamp_length_max(config.max_forward_velocity);
-            // update position
-            transform.position = transform.position + transform.velocity*dt;
+            transform.velocity = ((new_position - transform.position)/dt).clamp_length_max(config.max_forward_velocity);
+            transform.position = new_position;
         }
     }
+    /// Reduces the lateral (perpendicular to `dir`) component of an axle's velocity by a fraction
+    /// `grip*dt` per step, capped by `max_grip_force` so the tire slides once the required
+    /// correction exceeds what it can supply
+    fn apply_axle_grip(velocity: Vec2, dir: Vec2, grip: f32, max_grip_force: f32, dt: f32) -> Vec2{
+        let longitudinal = velocity.dot(dir);
+        let lateral = velocity.dot(dir.perp());
+        let correction = (lateral*grip*dt).clamp(-max_grip_force*dt, max_grip_force*dt);
+        dir*longitudinal + dir.perp()*(lateral - correction)
+    }
 }
 
 pub fn spawn_player_car(mut commands: Commands, mut meshes: ResMut<Assets<Mesh>>, mut materials: ResMut<Assets<ColorMaterial>>){
     commands.spawn((
         Player, Car, UserControlled, CarControls::default(), TrackTransform::default(),
+        PreviousPosition::default(), Tunneling::default(), LapProgress::default(),
+        PreviousVelocity::default(), ExperiencesGForce::default(),
         MaterialMesh2dBundle{
             mesh: bevy::sprite::Mesh2dHandle(meshes.add(Rectangle{half_size: Vec2::new(5.0, 2.5)})),
             material: materials.add(ColorMaterial{color: Color::RED, texture: None}),
@@ -170,22 +287,27 @@ pub struct CarPlugin;
 impl Plugin for CarPlugin{
     fn build(&self, app: &mut App) {
         app
-            .register_type::<TrackConfig>()
             .register_type::<CarPhysicsConfig>()
             .register_type::<Car>()
             .register_type::<UserControlled>()
             .register_type::<CarControls>()
             .register_type::<TrackTransform>()
             .register_type::<Player>()
+            .register_type::<PreviousPosition>()
+            .register_type::<Tunneling>()
+            .register_type::<PreviousVelocity>()
+            .register_type::<ExperiencesGForce>()
             .init_resource::<CarPhysicsConfig>()
-            .init_resource::<TrackConfig>()
-            .add_systems(PreUpdate, TrackConfig::update_scale)
             .add_systems(FixedUpdate, (
-                CarControls::read_player_input, 
-                TrackTransform::update_physics, 
+                CarControls::read_player_input,
+                Tunneling::record_previous_position,
+                ExperiencesGForce::record_previous_velocity,
+                TrackTransform::update_physics,
+                Tunneling::resolve_wall_collisions,
+                ExperiencesGForce::update_gforce,
                 TrackTransform::update_transform
             ).chain())
             .add_systems(Startup, spawn_player_car);
-        
+
     }
 }
@@ -8,8 +8,10 @@ use bevy_inspector_egui::quick::WorldInspectorPlugin;
 fn main() {
     let mut app = App::new();
     app.add_plugins((
-        DefaultPlugins, 
-        menu::MenuPlugin, 
+        DefaultPlugins,
+        track::TrackPlugin,
+        car::CarPlugin,
+        menu::MenuPlugin,
         WorldInspectorPlugin::new()
     ));
     app.add_systems(Startup, spawn_scene);
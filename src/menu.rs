@@ -1,10 +1,13 @@
 pub use bevy::prelude::*;
+use crate::car::{Player, ExperiencesGForce};
+use crate::track::{LapProgress, TrackSaveSlot};
 
 #[derive(Debug, Default, Clone, Reflect, PartialEq, Eq, Hash, States, Component)]
 pub enum AppState{
     #[default] PlayerControl,
     TrackCreation,
-    TrackRendering
+    TrackRendering,
+    Racing
 }
 
 #[derive(Debug, Clone, Reflect, Resource)]
@@ -18,7 +21,7 @@ impl Default for Canvas{
     fn default() -> Self {
         Self{
             half_extents: Vec2::new(800.0, 450.0), button_width: 150.0, scale: 1.0,
-            buttons: vec!["Play", "Create Track", "Finish Track"]
+            buttons: vec!["Play", "Create Track", "Finish Track", "Save Track", "Load Track", "Random Track"]
         }
     }
 }
@@ -43,9 +46,17 @@ impl Canvas{
         let Ok(window) = windows.get_single() else {return;};
         canvas.scale = (window.height() / (canvas.half_extents.y*2.0)).min(window.width() / (canvas.half_extents.x*2.0 + canvas.button_width));
     }
+    /// Colors the "Load Track" button to reflect whether its save slot is occupied
     pub fn update_button_colors(
-        
-    ){}
+        mut buttons: Query<(&ButtonName, &mut BackgroundColor)>,
+        slot: Res<TrackSaveSlot>
+    ){
+        for (name, mut color) in buttons.iter_mut(){
+            if name.0 == "Load Track"{
+                *color = if slot.is_occupied() {BackgroundColor(Color::rgb(0.7, 1.0, 0.7))} else {BackgroundColor(Color::WHITE)};
+            }
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, Reflect, Component)]
@@ -57,6 +68,10 @@ pub struct CanvasText(pub f32);
 #[derive(Default, Debug, Clone, Reflect, Component)]
 pub struct ButtonName(pub &'static str);
 
+/// Marker for the HUD text displaying the current/best lap time while racing
+#[derive(Default, Debug, Clone, Reflect, Component)]
+pub struct LapHud;
+
 pub fn spawn_menu(
     mut commands: Commands,
     canvas: Res<Canvas>
@@ -123,17 +138,54 @@ pub fn spawn_menu(
             },
             background_color: BackgroundColor(Color::BLACK),
             ..Default::default()
+        }).with_children(|parent| {
+            parent.spawn((TextBundle::from_section(
+                "Lap 0\n0.00s (Best: --)",
+                TextStyle{
+                    font_size: 24.0,
+                    color: Color::WHITE,
+                    ..Default::default()
+                },
+            ), CanvasText(24.0), LapHud));
         });
     });
 }
 
+/// System which handles the "Play" button, starting a race
+pub fn handle_play_button(
+    buttons: Query<(&Interaction, &ButtonName), Changed<Interaction>>,
+    mut next_state: ResMut<NextState<AppState>>
+){
+    for (interaction, name) in buttons.iter(){
+        if *interaction == Interaction::Pressed && name.0 == "Play"{
+            next_state.set(AppState::Racing);
+        }
+    }
+}
+
+/// System which keeps the lap HUD text in sync with the player's `LapProgress`
+pub fn update_lap_hud(
+    mut hud: Query<&mut Text, With<LapHud>>,
+    player: Query<(&LapProgress, Option<&ExperiencesGForce>), With<Player>>,
+    time: Res<Time>
+){
+    let Ok((progress, gforce)) = player.get_single() else {return;};
+    let Ok(mut text) = hud.get_single_mut() else {return;};
+    let elapsed = time.elapsed().saturating_sub(progress.lap_start);
+    let best = progress.best_lap.map_or_else(|| "--".to_string(), |best| format!("{:.2}s", best.as_secs_f32()));
+    let peak_g = gforce.map_or(0.0, |g| g.peak);
+    text.sections[0].value = format!("Lap {}\n{:.2}s (Best: {})\nHardest hit: {:.1}g", progress.lap, elapsed.as_secs_f32(), best, peak_g);
+}
+
 pub struct MenuPlugin;
 impl Plugin for MenuPlugin{
     fn build(&self, app: &mut App) {
         app.init_resource::<Canvas>()
+        .register_type::<LapHud>()
         .init_state::<AppState>()
         .add_systems(Startup, spawn_menu)
         .add_systems(PostUpdate, Canvas::scale_canvas_elements)
-        .add_systems(PreUpdate, Canvas::update_scale);
+        .add_systems(PreUpdate, Canvas::update_scale)
+        .add_systems(Update, (Canvas::update_button_colors, handle_play_button, update_lap_hud.run_if(in_state(AppState::Racing))));
     }
 }
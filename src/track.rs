@@ -0,0 +1,426 @@
+use std::f32::consts::TAU;
+use std::path::Path;
+use std::time::Duration;
+use bevy::prelude::*;
+use noise::{NoiseFn, Perlin};
+use serde::{Serialize, Deserialize};
+use crate::car::{Car, TrackTransform};
+use crate::menu::{AppState, ButtonName};
+
+/// A resource containing information about the track
+#[derive(Clone, Debug, Reflect, Resource)]
+pub struct TrackConfig{
+    /// Logical width and height of the track
+    pub logical_size: Vec2,
+    /// Scale of the track in the world coordinates
+    pub scale: f32,
+    /// Line segments making up the track's boundary walls, in track coordinates
+    pub boundary: Vec<(Vec2, Vec2)>
+}
+impl Default for TrackConfig{
+    fn default() -> Self {
+        Self{logical_size: Vec2::new(100.0, 100.0), scale: 1.0, boundary: Vec::new()}
+    }
+}
+impl TrackConfig{
+    /// Transforms a track position to a world position
+    pub fn track_to_world(&self, track_coords: Vec2)->Vec2{
+        (track_coords - self.logical_size*0.5)*self.scale
+    }
+    /// Transforms a world position to a track position
+    pub fn world_to_track(&self, world_pos: Vec2) -> Vec2{
+        (world_pos/self.scale) + self.logical_size*0.5
+    }
+    /// Updates the scale of the track to be as large as possible while keeping the entire track within bounds of the window
+    pub fn compute_scale(&mut self, window_size: Vec2){
+        self.scale = (window_size / self.logical_size).min_element();
+    }
+    /// A system which queries for window size and updates the scale accordingly
+    pub fn update_scale(mut config: ResMut<Self>, windows: Query<&Window>){
+        let Ok(window) = windows.get_single() else {return;};
+        config.compute_scale(Vec2::new(window.height(), window.width()));
+    }
+    /// Finds the earliest point at which the segment from `start` to `end` crosses the track
+    /// boundary, returning the hit point and the wall normal opposing the direction of travel.
+    /// Used for continuous collision detection against thin walls.
+    pub fn sweep_boundary(&self, start: Vec2, end: Vec2) -> Option<(Vec2, Vec2)>{
+        let travel = end - start;
+        let mut closest: Option<(f32, Vec2, Vec2)> = None;
+        for &(wall_start, wall_end) in self.boundary.iter(){
+            let wall = wall_end - wall_start;
+            let denom = travel.x*wall.y - travel.y*wall.x;
+            if denom.abs() < f32::EPSILON {continue;}
+            let diff = wall_start - start;
+            let t = (diff.x*wall.y - diff.y*wall.x)/denom;
+            let u = (diff.x*travel.y - diff.y*travel.x)/denom;
+            if !(0.0..=1.0).contains(&t) || !(0.0..=1.0).contains(&u) {continue;}
+            if closest.is_some_and(|(closest_t, _, _)| t >= closest_t) {continue;}
+            let mut normal = wall.perp().normalize_or_zero();
+            if normal.dot(travel) > 0.0 {normal = -normal;}
+            closest = Some((t, start + travel*t, normal));
+        }
+        closest.map(|(_, point, normal)| (point, normal))
+    }
+}
+
+/// A trigger zone in track space that a car must pass through, in order, to progress its lap
+#[derive(Clone, Debug, Reflect, Component)]
+pub struct Checkpoint{
+    /// Position of this checkpoint within the ordered lap sequence
+    pub index: u32,
+    /// Centre of the checkpoint's trigger rectangle, in track coordinates
+    pub position: Vec2,
+    /// Half width/height of the checkpoint's trigger rectangle
+    pub half_extents: Vec2
+}
+
+/// Marks the checkpoint that also serves as the start/finish line; crossing it completes a lap
+#[derive(Clone, Debug, Default, Reflect, Component)]
+pub struct StartFinish;
+
+/// A car's progress around the lap sequence: which checkpoint it needs next, its current lap,
+/// and the timing used to report lap splits
+#[derive(Clone, Debug, Reflect, Component)]
+pub struct LapProgress{
+    pub next_checkpoint: u32,
+    pub lap: u32,
+    pub lap_start: Duration,
+    pub best_lap: Option<Duration>
+}
+impl Default for LapProgress{
+    fn default() -> Self {
+        // Lap 0 begins by leaving the start/finish line (checkpoint 0), so the first checkpoint
+        // a fresh car must reach is checkpoint 1
+        Self{next_checkpoint: 1, lap: 0, lap_start: Duration::ZERO, best_lap: None}
+    }
+}
+
+/// Fired when a car completes a lap by crossing the start/finish line having already passed
+/// every other checkpoint in order
+#[derive(Debug, Clone, Event)]
+pub struct LapCompletedEvent{
+    pub car: Entity,
+    pub lap: u32,
+    pub time: Duration
+}
+
+/// System which resets every car's `LapProgress` on entering `AppState::Racing`, so a lap's timer
+/// starts from the moment racing begins rather than from app startup
+pub fn reset_lap_progress_on_race_start(
+    mut cars: Query<&mut LapProgress, With<Car>>,
+    time: Res<Time>
+){
+    for mut progress in cars.iter_mut(){
+        *progress = LapProgress{lap_start: time.elapsed(), ..Default::default()};
+    }
+}
+
+/// System which places every car at the start/finish line on entering `AppState::Racing`, facing
+/// towards checkpoint 1. Without this, a car left at `TrackTransform::default()` (track origin)
+/// sits nowhere near a generated or loaded loop and can never reach a checkpoint.
+pub fn reset_car_position_on_race_start(
+    mut cars: Query<&mut TrackTransform, With<Car>>,
+    checkpoints: Query<(&Checkpoint, Has<StartFinish>)>
+){
+    let Some(start) = checkpoints.iter().find_map(|(checkpoint, is_start_finish)| is_start_finish.then_some(checkpoint)) else {return;};
+    let next = checkpoints.iter().find_map(|(checkpoint, _)| (checkpoint.index == 1).then_some(checkpoint.position));
+    let rotation = next.map_or(0.0, |next_pos| (next_pos - start.position).to_angle());
+    for mut transform in cars.iter_mut(){
+        transform.position = start.position;
+        transform.rotation = rotation;
+        transform.velocity = Vec2::ZERO;
+    }
+}
+
+/// System which advances each car's `LapProgress` as it passes checkpoints in order, rejecting
+/// shortcuts, and emits `LapCompletedEvent` when the start/finish line is crossed in sequence
+pub fn update_checkpoints(
+    mut cars: Query<(Entity, &TrackTransform, &mut LapProgress), With<Car>>,
+    checkpoints: Query<(&Checkpoint, Has<StartFinish>)>,
+    time: Res<Time>,
+    mut lap_events: EventWriter<LapCompletedEvent>
+){
+    let checkpoint_count = checkpoints.iter().count() as u32;
+    if checkpoint_count == 0 {return;}
+    let elapsed = time.elapsed();
+    for (car, transform, mut progress) in cars.iter_mut(){
+        for (checkpoint, is_start_finish) in checkpoints.iter(){
+            if checkpoint.index != progress.next_checkpoint {continue;}
+            let local = transform.position - checkpoint.position;
+            if local.x.abs() > checkpoint.half_extents.x || local.y.abs() > checkpoint.half_extents.y {continue;}
+            if is_start_finish{
+                let lap_time = elapsed.saturating_sub(progress.lap_start);
+                progress.lap += 1;
+                progress.lap_start = elapsed;
+                if progress.best_lap.map_or(true, |best| lap_time < best){
+                    progress.best_lap = Some(lap_time);
+                }
+                lap_events.send(LapCompletedEvent{car, lap: progress.lap, time: lap_time});
+            }
+            progress.next_checkpoint = (checkpoint.index + 1) % checkpoint_count;
+            break;
+        }
+    }
+}
+
+/// A serializable snapshot of a player-authored track, captured in track coordinates so it can be
+/// written to disk and reconstructed later
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TrackData{
+    pub logical_size: Vec2,
+    pub centerline: Vec<Vec2>,
+    pub width: f32,
+    pub checkpoints: Vec<Vec2>
+}
+impl TrackData{
+    /// Offsets the centerline by `width/2` along each vertex's normal, returning the closed-loop
+    /// `(inner, outer)` vertex rings. `inner` is offset to the left of the direction of travel, so
+    /// it is the inside ring for a counter-clockwise centerline (as `generate_track` builds) and
+    /// the outside ring for a clockwise one
+    fn offset_loops(&self) -> (Vec<Vec2>, Vec<Vec2>){
+        let count = self.centerline.len();
+        let half_width = self.width*0.5;
+        let mut inner = Vec::with_capacity(count);
+        let mut outer = Vec::with_capacity(count);
+        for i in 0..count{
+            let prev = self.centerline[(i + count - 1) % count];
+            let next = self.centerline[(i + 1) % count];
+            let normal = (next - prev).normalize_or_zero().perp();
+            inner.push(self.centerline[i] + normal*half_width);
+            outer.push(self.centerline[i] - normal*half_width);
+        }
+        (inner, outer)
+    }
+    /// Offsets the centerline by `width/2` along each vertex's normal to build the closed-loop
+    /// inner and outer boundary segments
+    pub fn build_boundary(&self) -> Vec<(Vec2, Vec2)>{
+        if self.centerline.len() < 2 {return Vec::new();}
+        let (inner, outer) = self.offset_loops();
+        let count = inner.len();
+        let mut segments = Vec::with_capacity(count*2);
+        for i in 0..count{
+            let next = (i + 1) % count;
+            segments.push((inner[i], inner[next]));
+            segments.push((outer[i], outer[next]));
+        }
+        segments
+    }
+    /// Writes this track layout to `path` as RON, creating any missing parent directories
+    pub fn save(&self, path: &str) -> Result<(), String>{
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default()).map_err(|error| error.to_string())?;
+        if let Some(parent) = Path::new(path).parent(){
+            std::fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+        }
+        std::fs::write(path, text).map_err(|error| error.to_string())
+    }
+    /// Reads and parses a track layout previously written by `save`
+    pub fn load(path: &str) -> Result<Self, String>{
+        let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+        ron::de::from_str(&text).map_err(|error| error.to_string())
+    }
+    /// Rebuilds `TrackConfig`'s boundary and spawns a `Checkpoint` entity (the first doubling as
+    /// the start/finish line) for each stored checkpoint position
+    pub fn apply(&self, commands: &mut Commands, track_config: &mut TrackConfig){
+        track_config.logical_size = self.logical_size;
+        track_config.boundary = self.build_boundary();
+        for (index, &position) in self.checkpoints.iter().enumerate(){
+            let mut checkpoint = commands.spawn(Checkpoint{index: index as u32, position, half_extents: Vec2::splat(self.width*0.5)});
+            if index == 0 {checkpoint.insert(StartFinish);}
+        }
+    }
+}
+
+/// The on-disk save slot used by the "Save Track" / "Load Track" buttons
+#[derive(Clone, Debug, Reflect, Resource)]
+pub struct TrackSaveSlot{
+    pub path: String
+}
+impl Default for TrackSaveSlot{
+    fn default() -> Self {
+        Self{path: "assets/tracks/saved_track.ron".to_string()}
+    }
+}
+impl TrackSaveSlot{
+    /// Whether a track has already been saved to this slot
+    pub fn is_occupied(&self) -> bool {
+        Path::new(&self.path).exists()
+    }
+}
+
+/// Holds a track generated or loaded into memory, applied to the world the next time
+/// `AppState::TrackRendering` is entered. Takes priority over `TrackSaveSlot` so the "Random
+/// Track" button doesn't have to round-trip through disk. Not reflected since `TrackData` isn't.
+#[derive(Default, Resource)]
+pub struct PendingTrack(pub Option<TrackData>);
+
+/// The `TrackData` currently applied to the world, kept in sync by `apply_track_on_enter` so
+/// "Save Track" can persist whatever was generated or loaded rather than an unauthored layout.
+/// Not reflected since `TrackData` isn't.
+#[derive(Default, Resource)]
+pub struct ActiveTrack(pub Option<TrackData>);
+
+/// How many evenly-spaced checkpoints a generated track is given
+const GENERATED_CHECKPOINT_COUNT: usize = 8;
+/// How many centerline samples a generated track's radial noise field is evaluated at
+const GENERATED_CENTERLINE_SAMPLES: usize = 64;
+/// How many times `generate_track` retries with an incremented seed before giving up on avoiding
+/// a self-intersecting loop
+const GENERATED_MAX_ATTEMPTS: u32 = 32;
+
+/// Generates a random, raceable closed-loop track by sampling a radial noise field around the
+/// centre of `config.logical_size`. Retries with an incremented seed if the resulting boundary
+/// self-intersects, giving up and returning the last attempt after `GENERATED_MAX_ATTEMPTS` tries.
+pub fn generate_track(seed: u32, config: &TrackConfig) -> TrackData{
+    let center = config.logical_size*0.5;
+    let base_radius = config.logical_size.min_element()*0.3;
+    let amplitude = base_radius*0.3;
+    let width = base_radius*0.15;
+
+    let mut attempt = TrackData{logical_size: config.logical_size, centerline: Vec::new(), width, checkpoints: Vec::new()};
+    for offset in 0..GENERATED_MAX_ATTEMPTS{
+        let noise = Perlin::new(seed.wrapping_add(offset));
+        let centerline: Vec<Vec2> = (0..GENERATED_CENTERLINE_SAMPLES).map(|i| {
+            let theta = (i as f32/GENERATED_CENTERLINE_SAMPLES as f32)*TAU;
+            // Sample a point on the unit circle rather than theta directly, so the noise field
+            // wraps seamlessly and the centerline closes without a seam
+            let radius = base_radius + amplitude*(noise.get([theta.cos() as f64, theta.sin() as f64]) as f32);
+            center + Vec2::from_angle(theta)*radius
+        }).collect();
+        let checkpoints = evenly_spaced_checkpoints(&centerline, GENERATED_CHECKPOINT_COUNT);
+        attempt = TrackData{logical_size: config.logical_size, centerline, width, checkpoints};
+        let (inner, outer) = attempt.offset_loops();
+        if !loop_self_intersects(&inner) && !loop_self_intersects(&outer){
+            return attempt;
+        }
+    }
+    warn!("generate_track could not find a non-self-intersecting loop after {GENERATED_MAX_ATTEMPTS} attempts, using the last one generated");
+    attempt
+}
+
+/// Picks `count` evenly spaced vertices from a closed-loop centerline
+fn evenly_spaced_checkpoints(centerline: &[Vec2], count: usize) -> Vec<Vec2>{
+    let len = centerline.len();
+    (0..count).map(|i| centerline[i*len/count]).collect()
+}
+
+/// Whether any two non-adjacent edges of a single closed vertex loop properly cross each other.
+/// Edge `i` runs from `vertices[i]` to `vertices[i+1]` (wrapping); edges that share an endpoint
+/// (consecutive edges, and the last-to-first wrap) are skipped since touching there is expected.
+fn loop_self_intersects(vertices: &[Vec2]) -> bool{
+    let count = vertices.len();
+    if count < 4 {return false;}
+    for i in 0..count{
+        let (a1, a2) = (vertices[i], vertices[(i + 1) % count]);
+        for j in (i + 2)..count{
+            if i == 0 && j == count - 1 {continue;} // edge j wraps around to share a vertex with edge 0
+            let (b1, b2) = (vertices[j], vertices[(j + 1) % count]);
+            if segments_intersect(a1, a2, b1, b2) {return true;}
+        }
+    }
+    false
+}
+
+/// Whether segment `a1`-`a2` properly crosses segment `b1`-`b2`. Uses a strict orientation test
+/// so segments that merely touch at or pass through an endpoint don't count as crossing.
+fn segments_intersect(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool{
+    fn cross(a: Vec2, b: Vec2) -> f32 {a.x*b.y - a.y*b.x}
+    let d1 = cross(b2 - b1, a1 - b1);
+    let d2 = cross(b2 - b1, a2 - b1);
+    let d3 = cross(a2 - a1, b1 - a1);
+    let d4 = cross(a2 - a1, b2 - a1);
+    d1 != 0.0 && d2 != 0.0 && d3 != 0.0 && d4 != 0.0
+        && (d1 > 0.0) != (d2 > 0.0)
+        && (d3 > 0.0) != (d4 > 0.0)
+}
+
+/// A seed derived from the current time, used to vary each "Random Track" press
+fn random_seed() -> u32{
+    use std::time::{SystemTime, UNIX_EPOCH};
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.subsec_nanos())
+}
+
+/// System which handles the "Save Track" / "Load Track" / "Random Track" buttons. Saving
+/// persists whatever track is currently active (generated or loaded) out immediately; loading
+/// and random generation transition into `TrackRendering`, where `apply_track_on_enter` performs
+/// the actual reconstruction.
+pub fn handle_track_file_buttons(
+    buttons: Query<(&Interaction, &ButtonName), Changed<Interaction>>,
+    active: Res<ActiveTrack>,
+    track_config: Res<TrackConfig>,
+    slot: Res<TrackSaveSlot>,
+    mut pending: ResMut<PendingTrack>,
+    mut next_state: ResMut<NextState<AppState>>
+){
+    for (interaction, name) in buttons.iter(){
+        if *interaction != Interaction::Pressed {continue;}
+        match name.0{
+            "Save Track" => {
+                let Some(data) = active.0.as_ref() else {
+                    warn!("Save Track pressed with no active track; refusing to save an empty track");
+                    continue;
+                };
+                if let Err(error) = data.save(&slot.path){
+                    warn!("Failed to save track to {}: {error}", slot.path);
+                }
+            }
+            "Load Track" => {
+                pending.0 = None;
+                next_state.set(AppState::TrackRendering);
+            }
+            "Random Track" => {
+                pending.0 = Some(generate_track(random_seed(), &track_config));
+                next_state.set(AppState::TrackRendering);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// System which applies a track to the world on entering `AppState::TrackRendering`: a pending
+/// generated/loaded track takes priority, falling back to reloading `TrackSaveSlot` from disk.
+/// Records the applied layout into `ActiveTrack` so it can be re-saved later.
+pub fn apply_track_on_enter(
+    mut commands: Commands,
+    mut track_config: ResMut<TrackConfig>,
+    mut pending: ResMut<PendingTrack>,
+    mut active: ResMut<ActiveTrack>,
+    slot: Res<TrackSaveSlot>,
+    old_checkpoints: Query<Entity, With<Checkpoint>>
+){
+    let data = match pending.0.take(){
+        Some(data) => data,
+        None => match TrackData::load(&slot.path){
+            Ok(data) => data,
+            Err(_) => return
+        }
+    };
+    for entity in old_checkpoints.iter(){
+        commands.entity(entity).despawn();
+    }
+    data.apply(&mut commands, &mut track_config);
+    active.0 = Some(data);
+}
+
+/// Adds the track's boundary representation, scaling, checkpoint/lap subsystem, and persistence
+/// and procedural generation of track layouts to the game
+pub struct TrackPlugin;
+impl Plugin for TrackPlugin{
+    fn build(&self, app: &mut App) {
+        app
+            .register_type::<TrackConfig>()
+            .register_type::<Checkpoint>()
+            .register_type::<StartFinish>()
+            .register_type::<LapProgress>()
+            .register_type::<TrackSaveSlot>()
+            .init_resource::<TrackConfig>()
+            .init_resource::<TrackSaveSlot>()
+            .init_resource::<PendingTrack>()
+            .init_resource::<ActiveTrack>()
+            .add_event::<LapCompletedEvent>()
+            .add_systems(PreUpdate, TrackConfig::update_scale)
+            .add_systems(Update, handle_track_file_buttons)
+            .add_systems(OnEnter(AppState::TrackRendering), apply_track_on_enter)
+            .add_systems(OnEnter(AppState::Racing), (reset_lap_progress_on_race_start, reset_car_position_on_race_start))
+            .add_systems(FixedUpdate, update_checkpoints.run_if(in_state(AppState::Racing)));
+    }
+}